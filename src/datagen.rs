@@ -0,0 +1,115 @@
+// Generates per-block payloads that hit a requested dedupe ratio and
+// compression ratio, rather than either fully unique data (0% dedupe, 0%
+// compressible) or all-zero data (100% dedupe, 100% compressible). This is
+// what lets Thermite drive an array's dedupe/compression engine to a
+// specific efficiency target, e.g. for the 3PAR case `--pagesize` targets.
+//
+// Everything here is driven off the same seeded `lcg::LCG` used elsewhere,
+// so a `DedupeGenerator` built from the same seed, blocksize, ratios and
+// pool size reproduces byte-for-byte the same sequence of blocks on every
+// run -- no on-disk metadata needed for a verify pass to reconstruct it.
+
+use lcg::LCG;
+
+const POOL_SIZE: usize = 8;
+
+// The LCG is uniform over `0..128`, not `0..100` -- comparing against a
+// `% 100` reduction would skew low rolls, so scale the dedupe threshold
+// up into the LCG's own range instead.
+fn duplicates(roll: u64, dedupe_ratio: u8) -> bool {
+    roll < (dedupe_ratio as u64 * 128) / 100
+}
+
+pub struct DedupeGenerator {
+    blocksize: usize,
+    dedupe_ratio: u8,
+    compress_ratio: u8,
+    generator: LCG,
+    // Separate wide-state LCG for the unique prefix bytes: the 128-state
+    // `generator` would repeat every 128 bytes and allow only 128 prefixes.
+    bytes: LCG,
+    pool: Vec<Vec<u8>>,
+}
+
+impl DedupeGenerator {
+    pub fn new(seed: u64,
+               blocksize: usize,
+               dedupe_ratio: u8,
+               compress_ratio: u8)
+               -> DedupeGenerator {
+        DedupeGenerator {
+            blocksize: blocksize,
+            dedupe_ratio: dedupe_ratio,
+            compress_ratio: compress_ratio,
+            generator: LCG::new(seed, 128),
+            bytes: LCG::new(seed, 1 << 63),
+            pool: Vec::with_capacity(POOL_SIZE),
+        }
+    }
+
+    // Produces the next block: either a fresh unique buffer (which joins the
+    // rotating reference pool) or a replay of one already in the pool, rolled
+    // against `dedupe_ratio` so the long-run duplicate fraction matches it.
+    pub fn next_block(&mut self) -> Vec<u8> {
+        let roll = self.generator.next().unwrap();
+
+        if !self.pool.is_empty() && duplicates(roll, self.dedupe_ratio) {
+            let index = (self.generator.next().unwrap() as usize) % self.pool.len();
+            return self.pool[index].clone();
+        }
+
+        let block = self.unique_block();
+        if self.pool.len() == POOL_SIZE {
+            self.pool.remove(0);
+        }
+        self.pool.push(block.clone());
+        block
+    }
+
+    // Builds one never-before-seen buffer: a unique, incompressible prefix
+    // followed by a single repeated fill byte, run long enough that the
+    // requested fraction of the block compresses away.
+    fn unique_block(&mut self) -> Vec<u8> {
+        let compressible_len = (self.blocksize * self.compress_ratio as usize) / 100;
+        let unique_len = self.blocksize - compressible_len;
+
+        let mut block = Vec::with_capacity(self.blocksize);
+        for _ in 0..unique_len {
+            // Top byte of the 63-bit state; the low bits of an LCG have short periods.
+            block.push((self.bytes.next().unwrap() >> 55) as u8);
+        }
+        let fill_byte = self.generator.next().unwrap() as u8;
+        block.extend(vec![fill_byte; compressible_len]);
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedupe_threshold_matches_requested_percentage() {
+        // duplicates() sees every roll the LCG can produce (0..128), so
+        // the fraction of true results should track dedupe_ratio closely
+        // -- this is the scaling math ba6cedd had to hotfix.
+        for &ratio in &[0u8, 10, 30, 50, 90, 100] {
+            let hits = (0..128u64).filter(|&roll| duplicates(roll, ratio)).count();
+            let actual_pct = (hits as f64 / 128.0) * 100.0;
+            assert!((actual_pct - ratio as f64).abs() < 1.0,
+                    "requested {}% dedupe, threshold only hits {:.1}%",
+                    ratio,
+                    actual_pct);
+        }
+    }
+
+    #[test]
+    fn compress_ratio_sets_the_repeated_fill_length() {
+        let mut gen = DedupeGenerator::new(7, 100, 0, 40);
+        let block = gen.next_block();
+
+        let fill_byte = *block.last().unwrap();
+        let fill_len = block.iter().rev().take_while(|&&b| b == fill_byte).count();
+        assert!(fill_len >= 40, "fill run {} shorter than requested 40%", fill_len);
+    }
+}
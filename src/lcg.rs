@@ -0,0 +1,36 @@
+// A small linear congruential generator used to drive deterministic,
+// reproducible offset and data sequences (e.g. the `random100` I/O mode).
+//
+// The multiplier/increment pair below are the well-known constants used by
+// Knuth's MMIX generator, which give a full period over a power-of-two
+// modulus as long as the increment is odd -- so every value in
+// `0..modulus` is produced exactly once before the sequence repeats.
+
+pub struct LCG {
+    state: u64,
+    modulus: u64,
+    multiplier: u64,
+    increment: u64,
+}
+
+impl LCG {
+    pub fn new(seed: u64, modulus: u64) -> LCG {
+        LCG {
+            state: seed & (modulus.wrapping_sub(1)),
+            modulus: modulus,
+            multiplier: 6364136223846793005,
+            increment: 1442695040888963407,
+        }
+    }
+}
+
+impl Iterator for LCG {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        self.state = self.state
+            .wrapping_mul(self.multiplier)
+            .wrapping_add(self.increment) & (self.modulus.wrapping_sub(1));
+        Some(self.state)
+    }
+}
@@ -0,0 +1,36 @@
+// A minimal `log` backend: verbosity is controlled by repeated `-v` flags,
+// mapped straight onto `log::Level` (Warn by default, then Info, Debug,
+// Trace as `-v` is repeated). This keeps human diagnostics on stderr and
+// out of the way of whatever structured output a run writes to stdout.
+
+use log::{Level, Log, Metadata, Record, SetLoggerError};
+
+struct SimpleLogger {
+    level: Level,
+}
+
+impl Log for SimpleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init(verbosity: u8) -> Result<(), SetLoggerError> {
+    let level = match verbosity {
+        0 => Level::Warn,
+        1 => Level::Info,
+        2 => Level::Debug,
+        _ => Level::Trace,
+    };
+
+    log::set_boxed_logger(Box::new(SimpleLogger { level: level }))
+        .map(|()| log::set_max_level(level.to_level_filter()))
+}
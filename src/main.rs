@@ -15,22 +15,31 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 //
 
-extern crate getopts;
-extern crate rand;
+extern crate clap;
+#[macro_use]
+extern crate log;
 extern crate num;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
 
-use std::env;
 use std::process;
 use std::fs;
-use rand::Rng;
-use std::io::{Write, Seek, SeekFrom};
-use std::time::Instant;
+use std::io::{Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::time::{Duration, Instant};
 use std::thread;
-use std::sync::{Arc, Mutex};
-use getopts::Options;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::ops::Index;
 
+mod cli;
+mod datagen;
 mod lcg;
+mod logging;
+mod stats;
 mod watchdog;
 
 struct ThermiteOptions {
@@ -42,6 +51,12 @@ struct ThermiteOptions {
     endblock: u64,
     data: DataType,
     interval: u64,
+    seed: u64,
+    verify: bool,
+    dedupe_ratio: u8,
+    compress_ratio: u8,
+    report_interval: u64,
+    queue_depth: u64,
 }
 
 #[derive(PartialEq)]
@@ -58,8 +73,11 @@ enum DataType {
     Zero,
 }
 
-fn random_bytes(n: usize) -> Vec<u8> {
-    (0..n).map(|_| rand::random::<u8>()).collect()
+// Fills a buffer of `n` bytes from a seeded LCG so the payload is fully
+// reproducible: the same seed always yields the same initial buffer, which
+// a verify pass can then reconstruct without touching the disk.
+fn seeded_bytes(n: usize, generator: &mut lcg::LCG) -> Vec<u8> {
+    (0..n).map(|_| generator.next().unwrap() as u8).collect()
 }
 
 #[inline(always)]
@@ -67,159 +85,13 @@ fn zero(n: usize) -> Vec<u8> {
     vec![0; n]
 }
 
-fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options]", program);
-    print!("{}", opts.usage(&brief));
-}
-
 macro_rules! error_exit {
     ($errno:expr, $reason:expr) => {
-        println!($reason);
+        error!($reason);
         process::exit($errno);
     };
 }
 
-macro_rules! numeric_opt {
-    ($matched:expr, $parse_type:ty, $default:expr, $error:expr) => {
-        match $matched {
-            Some(x) => {
-                match x.parse::<$parse_type>() {
-                    Ok(x) => {
-                        if x == 0 {
-                            error_exit!(1, $error);
-                        } else { x }
-                    },
-                    Err(_) => {
-                        error_exit!(1, $error);
-                    },
-                }
-            },
-            None => { $default },
-        };
-    };
-}
-
-fn parse_opts(args: Vec<String>) -> ThermiteOptions {
-    // TODO Parameterize the defaults for the arguments
-    let program = args[0].clone();
-
-    let mut opts = Options::new();
-
-    opts.optflag("h", "help", "print this help text");
-    opts.optopt("m",
-                "mode",
-                "I/O mode, 'sequential' or 'sequentialreverse'  or 'random' or 'random100'",
-                "");
-    opts.optopt("d", "data", "datatype, 'random' or 'zero'", "");
-    opts.optopt("s",
-                "startblock",
-                "the starting block given the specified blocksize",
-                "");
-    opts.optopt("e",
-                "endblock",
-                "the ending block given the specified blocksize",
-                "");
-    opts.optopt("b", "blocksize", "block size to write", "");
-    opts.optopt("p", "pagesize", "dedupe page-size (16384 for 3PAR)", "");
-    opts.optopt("i",
-                "interval",
-                "number of blocks to skip between write ops",
-                "");
-    opts.optmulti("f", "file", "target file or block device", "/dev/sdX");
-
-    let matches = match opts.parse(&args[1..]) {
-        Ok(m) => m,
-        Err(f) => panic!(f.to_string()),
-    };
-
-    if matches.opt_present("h") {
-        print_usage(&program, opts);
-        process::exit(0);
-    }
-
-    let files_match = match matches.opt_strs("f").len() {
-        0 => {
-            error_exit!(1, "File is a required parameter.");
-        }
-        _ => matches.opt_strs("f"),
-    };
-
-    let mode_match = match matches.opt_str("m") {
-        Some(x) => {
-            match x.as_ref() {
-                "sequential" => IOMode::Sequential,
-                "sequentialreverse" => IOMode::SequentialReverse,
-                "random" => IOMode::Random,
-                "random100" => IOMode::Random100,
-                _ => {
-                    error_exit!(1, "I/O Mode must be sequential or random or random100");
-                }
-            }
-        }
-        None => IOMode::Random,
-    };
-
-    let data_match = match matches.opt_str("d") {
-        Some(y) => {
-            match y.as_ref() {
-                "random" => DataType::Random,
-                "zero" => DataType::Zero,
-                _ => {
-                    error_exit!(1, "Data type must be random or zero");
-                }
-            }
-        }
-        None => DataType::Random,
-    };
-
-    let blocksize_match = numeric_opt!(matches.opt_str("b"),
-                                       u64,
-                                       512,
-                                       "ERROR: Blocksize must be a positive power of 2.");
-    let pagesize_match = numeric_opt!(matches.opt_str("p"),
-                                      u64,
-                                      0,
-                                      "ERROR: Pagesize must be a positive power of 2.");
-    let startblock_match = numeric_opt!(matches.opt_str("s"),
-                                        u64,
-                                        0,
-                                        "ERROR: startblock must be a number.");
-    let endblock_match = numeric_opt!(matches.opt_str("e"),
-                                      u64,
-                                      0,
-                                      "ERROR: endblock must be a number.");
-    let interval_match = numeric_opt!(matches.opt_str("i"),
-                                      u64,
-                                      0,
-                                      "ERROR: block skip interval must be number.");
-
-    if (pagesize_match != 0) && (pagesize_match > blocksize_match) {
-        error_exit!(1,
-                    "ERROR: Pagesize, if supplied, must be smaller than blocksize.");
-    }
-    if (pagesize_match != 0) && (!pagesize_match.is_power_of_two()) {
-        error_exit!(1, "ERROR: Pagesize must be a power of 2");
-    }
-    if !blocksize_match.is_power_of_two() {
-        error_exit!(1, "ERROR: Blocksize must be a power of 2");
-    }
-    if (endblock_match != 0) && (endblock_match < startblock_match) {
-        error_exit!(1, "ERROR: Endblock must be higher than startblock");
-    }
-
-
-    ThermiteOptions {
-        blocksize: blocksize_match,
-        pagesize: pagesize_match,
-        target: files_match,
-        mode: mode_match,
-        startblock: startblock_match,
-        endblock: endblock_match,
-        data: data_match,
-        interval: interval_match,
-    }
-}
-
 fn run_io(fds: &[fs::File], args: &ThermiteOptions) -> std::io::Result<()> {
     // Check that all the supplied file descriptors are trivially the same length
     let length = fds.index(0).seek(SeekFrom::End(0)).unwrap();
@@ -240,27 +112,45 @@ fn run_io(fds: &[fs::File], args: &ThermiteOptions) -> std::io::Result<()> {
         start_block = args.startblock;
     }
 
+    if args.mode == IOMode::Random && end_block <= start_block {
+        error_exit!(1,
+                    "startblock/endblock (or a blocksize that collapses the file to 0 blocks) \
+                     leaves nothing for Random mode to pick from");
+    }
+
     let blockskip = args.interval;
 
-    println!("File length in blocks {}", end / args.blocksize);
-    println!("Start_Block {}", start_block);
-    println!("End_Block {}", end_block);
-    println!("Block Skip Interval: {}", blockskip);
+    info!("File length in blocks {}", end / args.blocksize);
+    info!("Start_Block {}", start_block);
+    info!("End_Block {}", end_block);
+    info!("Block Skip Interval: {}", blockskip);
 
     let mut iterations = 0;
+    let mut dedupe_gen = if args.dedupe_ratio != 0 || args.compress_ratio != 0 {
+        Some(datagen::DedupeGenerator::new(args.seed,
+                                            args.blocksize as usize,
+                                            args.dedupe_ratio,
+                                            args.compress_ratio))
+    } else {
+        None
+    };
+
     let mut data: Vec<u8>;
+    let mut data_generator = lcg::LCG::new(args.seed, 256);
     match args.data {
         DataType::Random => {
-            data = random_bytes(args.blocksize as usize);
+            data = match dedupe_gen {
+                Some(ref mut gen) => gen.next_block(),
+                None => seeded_bytes(args.blocksize as usize, &mut data_generator),
+            };
         }
         DataType::Zero => {
             data = zero(args.blocksize as usize);
         }
     };
 
-    let seed = rand::thread_rng().gen_range::<u64>(start_block, end_block);
     let power2 = (end_block - start_block).next_power_of_two();
-    let mut generator = lcg::LCG::new(seed, power2);
+    let mut generator = lcg::LCG::new(args.seed, power2);
 
     // Watchdog shared memory
     let last_io = Arc::new(Mutex::new(Instant::now()));
@@ -269,14 +159,99 @@ fn run_io(fds: &[fs::File], args: &ThermiteOptions) -> std::io::Result<()> {
         watchdog::watch(shared.clone(), 2u64, 3u64);
     });
 
+    let stats = Arc::new(Mutex::new(stats::Stats::new()));
+    if args.report_interval != 0 {
+        let shared_stats = stats.clone();
+        let report_interval = args.report_interval;
+        thread::spawn(move || {
+            stats::report_periodically(shared_stats, report_interval);
+        });
+    }
+
+    // Targets are shared across the worker pool via Arc, and addressed with
+    // positioned reads/writes (pread/pwrite) rather than seek+read/write, so
+    // concurrent workers never race over a shared file cursor.
+    let targets: Vec<Arc<fs::File>> = fds.iter()
+        .map(|f| Arc::new(f.try_clone().unwrap()))
+        .collect();
+
+    let queue_depth = args.queue_depth;
+    let (sender, receiver) = mpsc::sync_channel::<IoJob>(queue_depth as usize);
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    // `run_io` keeps its own clone of `receiver` alive for the whole
+    // function body, so the channel never actually disconnects when a
+    // worker dies early -- the producer would otherwise block in
+    // `send()` forever once the queue fills. Track live workers directly
+    // so the producer can notice and bail out instead of hanging.
+    let live_workers = Arc::new(AtomicUsize::new(queue_depth as usize));
+
+    let workers: Vec<thread::JoinHandle<std::io::Result<()>>> = (0..queue_depth)
+        .map(|_| {
+            let receiver = receiver.clone();
+            let targets = targets.clone();
+            let last_io = last_io.clone();
+            let stats = stats.clone();
+            let live_workers = live_workers.clone();
+            let verify = args.verify;
+            let blocksize = args.blocksize;
+
+            thread::spawn(move || -> std::io::Result<()> {
+                let result = (|| -> std::io::Result<()> {
+                    loop {
+                        let job = {
+                            let guard = receiver.lock().unwrap();
+                            guard.recv()
+                        };
+                        let job = match job {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        };
+
+                        for target in &targets {
+                            let io_start = Instant::now();
+                            if verify {
+                                let mut actual = zero(blocksize as usize);
+                                target.read_exact_at(&mut actual[..], job.offset)?;
+                                if actual != job.data {
+                                    report_miscompare(job.offset, blocksize, &job.data, &actual);
+                                    process::exit(1);
+                                }
+                            } else {
+                                target.write_all_at(&job.data[..], job.offset)?;
+                            }
+                            stats.lock().unwrap().record(blocksize, io_start.elapsed());
+
+                            let mut last_io_guard = last_io.lock().unwrap();
+                            *last_io_guard = Instant::now();
+                        }
+                    }
+                    Ok(())
+                })();
+                live_workers.fetch_sub(1, Ordering::SeqCst);
+                result
+            })
+        })
+        .collect();
+
+    // Offsets (and the data that goes with them) are produced sequentially
+    // on this thread so the mode/offset generators -- and the Random100 LCG
+    // sequence in particular -- stay identical between QD=1 and QD>1 runs.
     loop {
 
         let chosen_offset;
 
         match args.mode {
             IOMode::Random => {
-                let random = rand::thread_rng().gen_range(start_block, end_block);
-                chosen_offset = args.blocksize * random;
+                // generator is uniform over 0..power2, not over the
+                // requested block range, so reduce by rejection rather
+                // than `%` to avoid skewing low offsets -- same approach
+                // Random100 already uses below.
+                let mut random = generator.next().unwrap();
+                while random >= (end_block - start_block) {
+                    random = generator.next().unwrap();
+                }
+                chosen_offset = (random + start_block) * args.blocksize;
             }
             IOMode::Sequential => {
                 chosen_offset = (args.blocksize * iterations) + (start_block * args.blocksize);
@@ -302,20 +277,90 @@ fn run_io(fds: &[fs::File], args: &ThermiteOptions) -> std::io::Result<()> {
             }
         };
 
-        for mut fd in fds {
-            try!(fd.seek(SeekFrom::Start(chosen_offset)));
-            try!(fd.write(&data[..]));
-            let mut last_io_guard = last_io.lock().unwrap();
-            *last_io_guard = Instant::now();
+        // try_send (rather than the blocking send this replaced) lets us
+        // notice a dead worker pool directly instead of waiting on the
+        // channel to disconnect, which it never does while this
+        // function's own `receiver` clone is still alive.
+        let mut job = IoJob {
+            offset: chosen_offset,
+            data: data.clone(),
+        };
+        let mut workers_alive = true;
+        loop {
+            match sender.try_send(job) {
+                Ok(()) => break,
+                Err(mpsc::TrySendError::Disconnected(_)) => {
+                    workers_alive = false;
+                    break;
+                }
+                Err(mpsc::TrySendError::Full(unsent)) => {
+                    if live_workers.load(Ordering::SeqCst) == 0 {
+                        workers_alive = false;
+                        break;
+                    }
+                    job = unsent;
+                    thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+        if !workers_alive {
+            break;
         }
 
-        xor_scramble(&mut data, args.pagesize, iterations);
+        match dedupe_gen {
+            Some(ref mut gen) => data = gen.next_block(),
+            None => xor_scramble(&mut data, args.pagesize, iterations),
+        }
         iterations += 1 + blockskip;
     }
 
+    drop(sender);
+    for worker in workers {
+        worker.join().unwrap()?;
+    }
+
+    if args.verify {
+        println!("Verify passed: all blocks matched the expected data.");
+    }
+    stats.lock().unwrap().summary();
+
     Ok(())
 }
 
+// One unit of outstanding work handed from the offset producer to the
+// worker pool: the offset to act on and the data that belongs there
+// (generated on the producer thread so the sequence stays reproducible
+// regardless of queue depth).
+struct IoJob {
+    offset: u64,
+    data: Vec<u8>,
+}
+
+// Reports the LBA and first differing byte range of a verify miscompare.
+// Callers are expected to treat this as fatal and exit non-zero so a
+// verify run can gate storage acceptance tests.
+fn report_miscompare(offset: u64, blocksize: u64, expected: &[u8], actual: &[u8]) {
+    let lba = offset / blocksize;
+    let first_diff = expected.iter()
+        .zip(actual.iter())
+        .position(|(e, a)| e != a)
+        .unwrap_or(0);
+    let last_diff = expected.iter()
+        .zip(actual.iter())
+        .rposition(|(e, a)| e != a)
+        .unwrap_or(first_diff);
+
+    println!("MISCOMPARE at LBA {} (offset {}), differing bytes {}..={}, first differing \
+              byte at {}: expected {:#04x}, actual {:#04x}",
+             lba,
+             offset,
+             first_diff,
+             last_diff,
+             first_diff,
+             expected[first_diff],
+             actual[first_diff]);
+}
+
 fn xor_scramble(data: &mut Vec<u8>, pagesize: u64, offset: u64) {
     let blocksize = data.len() as u64;
 
@@ -341,17 +386,19 @@ fn xor_scramble(data: &mut Vec<u8>, pagesize: u64, offset: u64) {
 
 fn main() {
 
-    // Argparse
-    let args: Vec<String> = env::args().collect();
-    let thermite_args = parse_opts(args);
+    let thermite_args = cli::parse();
 
-    println!("Blocksize {}", thermite_args.blocksize);
-    println!("Pagesize {}", thermite_args.pagesize);
-    print!("Targets ");
-    for t in &thermite_args.target {
-        print!("{} ", t);
+    info!("Blocksize {}", thermite_args.blocksize);
+    info!("Pagesize {}", thermite_args.pagesize);
+    info!("Seed {}", thermite_args.seed);
+    info!("Mode: {}", if thermite_args.verify { "verify" } else { "write" });
+    info!("Dedupe ratio {}%", thermite_args.dedupe_ratio);
+    info!("Compress ratio {}%", thermite_args.compress_ratio);
+    if thermite_args.report_interval != 0 {
+        info!("Reporting every {}s", thermite_args.report_interval);
     }
-    println!("");
+    info!("Queue depth {}", thermite_args.queue_depth);
+    info!("Targets {}", thermite_args.target.join(" "));
 
     let mut options = fs::OpenOptions::new();
     options.read(true).write(true);
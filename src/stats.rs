@@ -0,0 +1,175 @@
+// Accumulates per-I/O latency and byte counts behind a shared mutex so the
+// I/O worker threads, a periodic reporter thread, and the final summary can all see
+// the same counters. Latency percentiles come from a coarse, fixed-bucket
+// microsecond histogram: O(1) per sample, no sorting needed.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Upper bound (in microseconds) of each histogram bucket. A sample at or
+// above the last bucket's bound is folded into it.
+const BUCKET_BOUNDS_US: [u64; 12] = [50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000,
+                                      50_000, 100_000, u64::max_value()];
+
+pub struct Stats {
+    start: Instant,
+    blocks: u64,
+    bytes: u64,
+    buckets: [u64; 12],
+    reported_blocks: u64,
+    reported_bytes: u64,
+    last_report: Instant,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        let now = Instant::now();
+        Stats {
+            start: now,
+            blocks: 0,
+            bytes: 0,
+            buckets: [0; 12],
+            reported_blocks: 0,
+            reported_bytes: 0,
+            last_report: now,
+        }
+    }
+
+    pub fn record(&mut self, bytes: u64, latency: Duration) {
+        self.blocks += 1;
+        self.bytes += bytes;
+
+        let micros = (latency.as_secs() * 1_000_000) + (latency.subsec_nanos() as u64 / 1_000);
+        let bucket = BUCKET_BOUNDS_US.iter()
+            .position(|&bound| micros <= bound)
+            .unwrap_or(BUCKET_BOUNDS_US.len() - 1);
+        self.buckets[bucket] += 1;
+    }
+
+    fn percentile(&self, fraction: f64) -> u64 {
+        let total: u64 = self.buckets.iter().sum();
+        if total == 0 {
+            return 0;
+        }
+
+        let target = (total as f64 * fraction).ceil() as u64;
+        let mut seen = 0u64;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return BUCKET_BOUNDS_US[i];
+            }
+        }
+        *BUCKET_BOUNDS_US.last().unwrap()
+    }
+
+    pub fn p50(&self) -> u64 {
+        self.percentile(0.50)
+    }
+
+    pub fn p99(&self) -> u64 {
+        self.percentile(0.99)
+    }
+
+    fn mean_latency_us(&self) -> u64 {
+        let samples: u64 = self.buckets.iter().sum();
+        if samples == 0 {
+            return 0;
+        }
+        // Approximate the mean from the bucket boundaries, since individual
+        // samples aren't retained. The open-ended last bucket is weighted at
+        // the last finite bound.
+        let last_finite = BUCKET_BOUNDS_US[BUCKET_BOUNDS_US.len() - 2];
+        let weighted: u64 = self.buckets
+            .iter()
+            .zip(BUCKET_BOUNDS_US.iter())
+            .map(|(&count, &bound)| count * bound.min(last_finite))
+            .sum();
+        weighted / samples
+    }
+
+    fn elapsed_secs(&self, since: Instant) -> f64 {
+        let elapsed = since.elapsed();
+        elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64 / 1_000_000_000.0)
+    }
+
+    // Prints a rolling line covering I/O since the previous report, then
+    // resets the rolling counters (the histogram itself stays cumulative).
+    fn report(&mut self) {
+        let secs = self.elapsed_secs(self.last_report);
+        let blocks = self.blocks - self.reported_blocks;
+        let bytes = self.bytes - self.reported_bytes;
+
+        let iops = if secs > 0.0 { blocks as f64 / secs } else { 0.0 };
+        let mbps = if secs > 0.0 {
+            (bytes as f64 / secs) / (1024.0 * 1024.0)
+        } else {
+            0.0
+        };
+
+        println!("IOPS: {:.0}  MB/s: {:.2}  p50: {}us  p99: {}us",
+                 iops,
+                 mbps,
+                 self.p50(),
+                 self.p99());
+
+        self.reported_blocks = self.blocks;
+        self.reported_bytes = self.bytes;
+        self.last_report = Instant::now();
+    }
+
+    // Prints the end-of-run totals: block count, elapsed time, mean and
+    // percentile latency, and achieved bandwidth.
+    pub fn summary(&self) {
+        let secs = self.elapsed_secs(self.start);
+        let mbps = if secs > 0.0 {
+            (self.bytes as f64 / secs) / (1024.0 * 1024.0)
+        } else {
+            0.0
+        };
+
+        println!("--- Summary ---");
+        println!("Total blocks: {}", self.blocks);
+        println!("Total bytes: {}", self.bytes);
+        println!("Elapsed: {:.2}s", secs);
+        println!("Mean latency: {}us", self.mean_latency_us());
+        println!("p50 latency: {}us", self.p50());
+        println!("p99 latency: {}us", self.p99());
+        println!("Achieved bandwidth: {:.2} MB/s", mbps);
+    }
+}
+
+// Prints a rolling stats line every `interval` seconds, looping forever on
+// the calling thread; `run_io` runs it on a thread of its own.
+pub fn report_periodically(stats: Arc<Mutex<Stats>>, interval: u64) {
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+        stats.lock().unwrap().report();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_fall_in_the_recorded_buckets() {
+        let mut stats = Stats::new();
+        for &us in &[10u64, 10, 10, 10, 2_000] {
+            stats.record(4096, Duration::from_micros(us));
+        }
+
+        assert_eq!(stats.p50(), 50);
+        assert_eq!(stats.p99(), 2_500);
+    }
+
+    #[test]
+    fn mean_latency_is_bucket_weighted() {
+        let mut stats = Stats::new();
+        stats.record(4096, Duration::from_micros(10));
+        stats.record(4096, Duration::from_micros(10));
+
+        assert_eq!(stats.mean_latency_us(), 50);
+    }
+}
@@ -0,0 +1,28 @@
+// Background thread that keeps an eye on I/O liveness. The I/O workers in
+// `run_io` stamp `last_io` every time they complete a read or write; if
+// this thread ever finds
+// that timestamp older than `interval * timeout` seconds, the run is
+// considered hung and the process is killed rather than left to block
+// forever against a wedged device.
+
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub fn watch(last_io: Arc<Mutex<Instant>>, interval: u64, timeout: u64) {
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+
+        let elapsed = {
+            let guard = last_io.lock().unwrap();
+            guard.elapsed()
+        };
+
+        if elapsed.as_secs() >= interval * timeout {
+            error!("Watchdog: no I/O observed in {} seconds, exiting.",
+                   elapsed.as_secs());
+            process::exit(2);
+        }
+    }
+}